@@ -1,11 +1,21 @@
 use vrf::{VRF, openssl::ECVRF};
-use statrs::distribution::{Binomial, Univariate};
 use num_bigint::BigUint;
-use num_traits::cast::ToPrimitive;
+use num_traits::{One, Zero};
+use sha2::{Digest, Sha256};
 
 type PublicKey<'a> = &'a [u8];
 type SecretKey<'a> = &'a [u8];
 
+// Domain-separates the VRF input so the same (sk, seed) can't be replayed across roles.
+fn election_input(seed: &[u8], role: u64) -> std::vec::Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(&(seed.len() as u64).to_be_bytes());
+    hasher.update(seed);
+    hasher.update(&role.to_be_bytes());
+
+    return hasher.finalize().to_vec();
+}
+
 fn get_largest(length: usize) -> BigUint {
     let length = length * 2;
     let fs = std::iter::once('f').cycle().take(length).collect::<String>();
@@ -14,47 +24,150 @@ fn get_largest(length: usize) -> BigUint {
     return largest;
 }
 
-fn lottery(hash: std::vec::Vec<u8>, threshold: f64, money: u64, total_money: u64) -> u64 {
-    let p = threshold / (total_money as f64);
-    let dist = Binomial::new(p, money).unwrap();
+// BigUint::pow takes a u32 exponent, which would silently truncate `money`;
+// this does exponentiation by squaring over the full u64 range instead.
+fn big_pow(base: &BigUint, exp: u64) -> BigUint {
+    let mut result = BigUint::one();
+    let mut base = base.clone();
+    let mut exp = exp;
 
-    let num = BigUint::from_bytes_be(&hash).to_f64().unwrap();
-    let denom = get_largest(hash.len()).to_f64().unwrap();
-    let ratio = num / denom;
-    
-    for i in 0..money {
-        let boundary = dist.cdf(i as f64);
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = &result * &base;
+        }
+        base = &base * &base;
+        exp >>= 1;
+    }
 
-        if ratio <= boundary {
-            return i;
-        } 
+    return result;
+}
+
+// Smallest k with hash_int / denom <= cumulative_k / b^money, where cumulative_k / b^money is the CDF of
+// Binomial(money, a / b) at k. Walks k forward one term at a time via
+// term_{k+1} = term_k * (money - k) * a / ((k + 1) * (b - a)) (always an exact division),
+// keeping only the current term and running sum alive instead of a table of money+1 BigUints
+// (each near the binomial mode is close to the full b^money bit length, so for
+// microAlgo-scale `money` retaining all of them is the memory bottleneck, not the lookup).
+// a == 0 or b_minus_a == 0 (p = 0 or p = 1) are degenerate and answered directly.
+fn lottery(hash: std::vec::Vec<u8>, threshold: u64, money: u64, total_money: u64) -> u64 {
+    assert!(threshold <= total_money, "threshold must not exceed total_money");
+
+    let a = BigUint::from(threshold);
+    let b = BigUint::from(total_money);
+
+    if a.is_zero() {
+        return 0;
+    }
+
+    let b_minus_a = &b - &a;
+    if b_minus_a.is_zero() {
+        return money;
+    }
+
+    let hash_int = BigUint::from_bytes_be(&hash);
+    let denom = get_largest(hash.len());
+    let b_pow_money = big_pow(&b, money);
+    let lhs = &hash_int * &b_pow_money;
+
+    let mut term = big_pow(&b_minus_a, money);
+    let mut cumulative = term.clone();
+
+    for k in 0..money {
+        if &cumulative * &denom >= lhs {
+            return k;
+        }
+
+        term = (term * BigUint::from(money - k) * &a) / (BigUint::from(k + 1) * &b_minus_a);
+        cumulative += &term;
     }
 
     return money;
 }
 
-pub fn check_select(sk: SecretKey, seed: &[u8], threshold: f64, // TODO: add a role: int parameter
-                    money: u64, total_money: u64, vrf: &mut ECVRF) -> (u64, std::vec::Vec<u8>) {
+// Lets callers swap cipher suites or VRF implementations without changing check_select/verify_select.
+pub trait VrfBackend {
+    type Error;
+
+    fn prove(&mut self, sk: &[u8], alpha: &[u8]) -> Result<std::vec::Vec<u8>, Self::Error>;
+    fn proof_to_hash(&mut self, pi: &[u8]) -> Result<std::vec::Vec<u8>, Self::Error>;
+    fn verify(&mut self, pk: &[u8], pi: &[u8], alpha: &[u8]) -> Result<std::vec::Vec<u8>, Self::Error>;
+    fn derive_public_key(&mut self, sk: &[u8]) -> Result<std::vec::Vec<u8>, Self::Error>;
+}
+
+impl VrfBackend for ECVRF {
+    type Error = vrf::openssl::Error;
+
+    fn prove(&mut self, sk: &[u8], alpha: &[u8]) -> Result<std::vec::Vec<u8>, Self::Error> {
+        VRF::prove(self, sk, alpha)
+    }
+
+    fn proof_to_hash(&mut self, pi: &[u8]) -> Result<std::vec::Vec<u8>, Self::Error> {
+        VRF::proof_to_hash(self, pi)
+    }
+
+    fn verify(&mut self, pk: &[u8], pi: &[u8], alpha: &[u8]) -> Result<std::vec::Vec<u8>, Self::Error> {
+        VRF::verify(self, pk, pi, alpha)
+    }
+
+    fn derive_public_key(&mut self, sk: &[u8]) -> Result<std::vec::Vec<u8>, Self::Error> {
+        ECVRF::derive_public_key(self, sk)
+    }
+}
+
+// SECP256K1's group order n, for use with generate_keypair.
+pub fn secp256k1_order() -> BigUint {
+    BigUint::parse_bytes(b"FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141", 16).unwrap()
+}
+
+// Rejection-samples a secret scalar from the OS CSPRNG and derives its public key.
+pub fn generate_keypair<B: VrfBackend>(vrf: &mut B, order: &BigUint) -> Result<(std::vec::Vec<u8>, std::vec::Vec<u8>), B::Error> {
+    let key_len = (order.bits() as usize + 7) / 8;
+
+    let scalar = loop {
+        let mut bytes = vec![0u8; key_len];
+        getrandom::getrandom(&mut bytes).expect("OS CSPRNG should not fail");
+
+        let candidate = BigUint::from_bytes_be(&bytes);
+        if !candidate.is_zero() && candidate < *order {
+            break candidate;
+        }
+    };
+
+    let mut secret_key = vec![0u8; key_len];
+    let scalar_bytes = scalar.to_bytes_be();
+    secret_key[key_len - scalar_bytes.len()..].copy_from_slice(&scalar_bytes);
+
+    let public_key = vrf.derive_public_key(&secret_key)?;
+
+    return Ok((secret_key, public_key));
+}
+
+pub fn check_select<B: VrfBackend>(sk: SecretKey, seed: &[u8], threshold: u64, role: u64,
+                    money: u64, total_money: u64, vrf: &mut B) -> (u64, std::vec::Vec<u8>)
+                    where B::Error: std::fmt::Debug {
 
-    let pi = vrf.prove(&sk, &seed).unwrap();
+    let input = election_input(seed, role);
+    let pi = vrf.prove(&sk, &input).unwrap();
     let hash = vrf.proof_to_hash(&pi).unwrap();
-    
+
     let lottery_num = lottery(hash, threshold, money, total_money);
 
     return (lottery_num, pi);
 }
 
-pub fn verify_select(pk: PublicKey, pi: std::vec::Vec<u8>, seed: &[u8], vrf: &mut ECVRF) -> u64 {
+pub fn verify_select<B: VrfBackend>(pk: PublicKey, pi: std::vec::Vec<u8>, seed: &[u8], threshold: u64, role: u64,
+                    money: u64, total_money: u64, vrf: &mut B) -> Option<u64> {
+
+    let input = election_input(seed, role);
+    let beta = vrf.verify(&pk, &pi, &input);
 
-    let beta = vrf.verify(&pk, &pi, &seed);
-    
     match beta {
         Ok(beta) => {
-            return 1;
+            return Some(lottery(beta, threshold, money, total_money));
         }
-        
-        Err(e) => {
-            return 0;
+
+        Err(_e) => {
+            return None;
         }
     }
 }
@@ -64,7 +177,6 @@ mod tests {
     use super::*;
     use vrf::openssl::{CipherSuite, ECVRF};
     use hex;
-    use uuid::Uuid;
     use num_bigint::ToBigUint;
     
     #[test]
@@ -74,6 +186,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn generate_keypair_test() {
+        let mut vrf = ECVRF::from_suite(CipherSuite::SECP256K1_SHA256_TAI).expect("VRF should init");
+        let order = secp256k1_order();
+
+        let (secret_key, public_key) = generate_keypair(&mut vrf, &order).unwrap();
+
+        assert!(!secret_key.iter().all(|b| *b == 0));
+        assert!(BigUint::from_bytes_be(&secret_key) < order);
+        assert_eq!(public_key, vrf.derive_public_key(&secret_key).unwrap());
+    }
+
     #[test]
     fn test_selection_probability() {
         let mut vrf = ECVRF::from_suite(CipherSuite::SECP256K1_SHA256_TAI).expect("VRF should init");
@@ -82,17 +206,17 @@ mod tests {
         
         // change this to random number
         let t_percentage = 0.5;
-        let threshold: f64 = t_percentage * (total_money as f64);
-        
+        let threshold: u64 = (t_percentage * (total_money as f64)) as u64;
+
          // change this to random number
         let money: u64 = 500000;
         let m_percentage = (money as f64) / (total_money as f64);
 
-        let secret_key = Uuid::new_v4().as_bytes().clone();
-        let public_key = vrf.derive_public_key(&secret_key).unwrap();
+        let (secret_key, public_key) = generate_keypair(&mut vrf, &secp256k1_order()).unwrap();
 
         let seed: &[u8] = b"random_seed";
-        let (practical, pi) = check_select(&secret_key, seed, threshold, money, total_money, &mut vrf);
+        let role: u64 = 0; // block proposer
+        let (practical, pi) = check_select(&secret_key, seed, threshold, role, money, total_money, &mut vrf);
         let theory = t_percentage * m_percentage * (total_money as f64);
 
         // this should be an average
@@ -100,7 +224,53 @@ mod tests {
         assert!(((practical as f64)- theory).abs() <= 0.01 * theory);
 
         // abstract this out
-        assert_eq!(verify_select(&public_key, pi, seed, &mut vrf), 1);
+        assert_eq!(verify_select(&public_key, pi, seed, threshold, role, money, total_money, &mut vrf), Some(practical));
+    }
+
+    #[test]
+    fn test_role_domain_separation() {
+        let mut vrf = ECVRF::from_suite(CipherSuite::SECP256K1_SHA256_TAI).expect("VRF should init");
+        let total_money: u64 = 1000000;
+        let threshold: u64 = 500000;
+        let money: u64 = 500000;
+
+        let (secret_key, public_key) = generate_keypair(&mut vrf, &secp256k1_order()).unwrap();
+
+        let seed: &[u8] = b"random_seed";
+        let (_, pi_proposer) = check_select(&secret_key, seed, threshold, 0, money, total_money, &mut vrf);
+        let (_, pi_committee) = check_select(&secret_key, seed, threshold, 1, money, total_money, &mut vrf);
+
+        assert_ne!(pi_proposer, pi_committee);
+        assert_eq!(verify_select(&public_key, pi_proposer, seed, threshold, 1, money, total_money, &mut vrf), None);
+    }
+
+    #[test]
+    fn test_selection_at_probability_extremes() {
+        let mut vrf = ECVRF::from_suite(CipherSuite::SECP256K1_SHA256_TAI).expect("VRF should init");
+        let total_money: u64 = 1000000;
+        let money: u64 = 500000;
+
+        let (secret_key, public_key) = generate_keypair(&mut vrf, &secp256k1_order()).unwrap();
+        let seed: &[u8] = b"random_seed";
+
+        // threshold == total_money: certain selection (p = 1), must not panic.
+        let (all_selected, pi) = check_select(&secret_key, seed, total_money, 0, money, total_money, &mut vrf);
+        assert_eq!(all_selected, money);
+        assert_eq!(verify_select(&public_key, pi, seed, total_money, 0, money, total_money, &mut vrf), Some(money));
+
+        // threshold == 0: certain non-selection (p = 0).
+        let (none_selected, pi) = check_select(&secret_key, seed, 0, 0, money, total_money, &mut vrf);
+        assert_eq!(none_selected, 0);
+        assert_eq!(verify_select(&public_key, pi, seed, 0, 0, money, total_money, &mut vrf), Some(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "threshold must not exceed total_money")]
+    fn test_selection_rejects_threshold_above_total_money() {
+        let mut vrf = ECVRF::from_suite(CipherSuite::SECP256K1_SHA256_TAI).expect("VRF should init");
+        let (secret_key, _) = generate_keypair(&mut vrf, &secp256k1_order()).unwrap();
+
+        check_select(&secret_key, b"random_seed", 2, 0, 1, 1, &mut vrf);
     }
 
     #[test]
@@ -113,8 +283,8 @@ mod tests {
         let seed: &[u8] = b"random_seed";
 
          // VRF proof and hash output
-        let pi = vrf.prove(&secret_key, &seed).unwrap();
-        let hash = vrf.proof_to_hash(&pi).unwrap();
+        let pi = VRF::prove(&mut vrf, &secret_key, &seed).unwrap();
+        let hash = VRF::proof_to_hash(&mut vrf, &pi).unwrap();
         let num = BigUint::from_bytes_be(&hash);
         let fatty = get_largest(1);
 